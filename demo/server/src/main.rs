@@ -1,13 +1,44 @@
 #[macro_use]
 extern crate log;
 
-use naia_server_socket::{LinkConditionerConfig, Packet, ServerSocket};
+use naia_server_socket::{LinkConditionerConfig, Packet, ServerSocket, ServerSocketConfig};
 use simple_logger;
 use smol::io;
 
 const PING_MSG: &str = "ping";
 const PONG_MSG: &str = "pong";
 
+const ECHO_TEXT_FLAG: &str = "--echo-text";
+const ECHO_BINARY_FLAG: &str = "--echo-binary";
+
+/// How the example server responds to incoming packets
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EchoMode {
+    /// Interpret payloads as text, and answer every "ping" with a "pong"
+    Text,
+    /// Send every payload back to its sender unchanged, byte-for-byte
+    Binary,
+}
+
+impl EchoMode {
+    fn from_args() -> EchoMode {
+        let mut mode = EchoMode::Text;
+        for arg in std::env::args().skip(1) {
+            match arg.as_str() {
+                ECHO_TEXT_FLAG => mode = EchoMode::Text,
+                ECHO_BINARY_FLAG => mode = EchoMode::Binary,
+                _ => {
+                    warn!(
+                        "Unknown argument: {}, expected {} or {}",
+                        arg, ECHO_TEXT_FLAG, ECHO_BINARY_FLAG
+                    );
+                }
+            }
+        }
+        mode
+    }
+}
+
 fn main() -> io::Result<()> {
     // IP Address to listen on for the signaling portion of WebRTC
     let session_listen_addr = "127.0.0.1:14191"
@@ -27,12 +58,19 @@ fn main() -> io::Result<()> {
     smol::block_on(async {
         simple_logger::init_with_level(log::Level::Info).expect("A logger was already initialized");
 
-        info!("Naia Server Socket Example Started");
+        let echo_mode = EchoMode::from_args();
+
+        info!("Naia Server Socket Example Started ({:?} echo)", echo_mode);
 
-        let mut server_socket =
-            ServerSocket::listen(session_listen_addr, webrtc_listen_addr, public_webrtc_addr)
-                .await
-                .with_link_conditioner(&LinkConditionerConfig::good_condition());
+        let mut server_socket = ServerSocket::listen(
+            session_listen_addr,
+            webrtc_listen_addr,
+            public_webrtc_addr,
+            ServerSocketConfig::default(),
+        )
+        .await
+        .expect("could not start server socket")
+        .with_link_conditioner(&LinkConditionerConfig::good_condition());
 
         let mut sender = server_socket.get_sender();
 
@@ -40,16 +78,30 @@ fn main() -> io::Result<()> {
             match server_socket.receive().await {
                 Ok(packet) => {
                     let address = packet.address();
-                    let message = String::from_utf8_lossy(packet.payload());
-                    info!("Server recv <- {}: {}", address, message);
-
-                    if message.eq(PING_MSG) {
-                        let to_client_message: String = PONG_MSG.to_string();
-                        info!("Server send -> {}: {}", address, to_client_message);
-                        sender
-                            .send(Packet::new(address, to_client_message.into_bytes()))
-                            .await
-                            .expect("send error");
+
+                    match echo_mode {
+                        EchoMode::Text => {
+                            let message = String::from_utf8_lossy(packet.payload());
+                            info!("Server recv <- {}: {}", address, message);
+
+                            if message.eq(PING_MSG) {
+                                let to_client_message: String = PONG_MSG.to_string();
+                                info!("Server send -> {}: {}", address, to_client_message);
+                                sender
+                                    .send(Packet::new(address, to_client_message.into_bytes()))
+                                    .await
+                                    .expect("send error");
+                            }
+                        }
+                        EchoMode::Binary => {
+                            let payload = packet.payload().to_vec();
+                            info!("Server recv <- {}: {:?}", address, payload);
+                            info!("Server send -> {}: {:?}", address, payload);
+                            sender
+                                .send(Packet::new(address, payload))
+                                .await
+                                .expect("send error");
+                        }
                     }
                 }
                 Err(error) => {
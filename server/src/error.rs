@@ -1,5 +1,7 @@
 use std::{error::Error, fmt, net::SocketAddr};
 
+use crate::Packet;
+
 /// An Error type specifically related to the Naia Server Socket
 /// This is under construction and needs to be cleaned up
 #[derive(Debug)]
@@ -8,6 +10,13 @@ pub enum NaiaServerSocketError {
     Wrapped(Box<dyn Error + Send + Sync>),
     /// An error indicating an inability to send to the given address
     SendError(SocketAddr),
+    /// An error indicating the queue of messages waiting to be sent is full.
+    /// Contains the Packet which could not be queued, so that it can be
+    /// dropped or coalesced with later messages
+    QueueFull(Packet),
+    /// An error indicating the client at the given address sent a payload
+    /// larger than the configured maximum
+    PayloadTooLarge(SocketAddr),
 }
 
 impl fmt::Display for NaiaServerSocketError {
@@ -15,6 +24,16 @@ impl fmt::Display for NaiaServerSocketError {
         match self {
             NaiaServerSocketError::Wrapped(boxed_err) => fmt::Display::fmt(boxed_err.as_ref(), f),
             NaiaServerSocketError::SendError(addr) => fmt::Display::fmt(&addr, f),
+            NaiaServerSocketError::QueueFull(packet) => {
+                write!(
+                    f,
+                    "send queue is full, could not queue packet to {}",
+                    packet.address()
+                )
+            }
+            NaiaServerSocketError::PayloadTooLarge(addr) => {
+                write!(f, "payload from {} exceeds the maximum size", addr)
+            }
         }
     }
 }
@@ -3,46 +3,96 @@ use async_trait::async_trait;
 use futures_channel::mpsc;
 use futures_util::{pin_mut, select, FutureExt, StreamExt};
 use std::{
-    io::Error as IoError,
+    io::{Error as IoError, ErrorKind},
     net::{SocketAddr, UdpSocket},
 };
 
 use naia_socket_shared::LinkConditionerConfig;
 
-use crate::{error::NaiaServerSocketError, Packet, ServerSocketTrait};
+use crate::{
+    error::NaiaServerSocketError, IpFilter, Packet, ServerSocketConfig, ServerSocketMetrics,
+    ServerSocketTrait,
+};
 
 use crate::{link_conditioner::LinkConditioner, message_sender::MessageSender};
 
-const CLIENT_CHANNEL_SIZE: usize = 8;
-
 /// A socket server which communicates with clients using an underlying
 /// unordered & unreliable network protocol
 #[derive(Debug)]
 pub struct ServerSocket {
     socket: Async<UdpSocket>,
+    local_addr: SocketAddr,
     to_client_sender: mpsc::Sender<Packet>,
     to_client_receiver: mpsc::Receiver<Packet>,
     receive_buffer: Vec<u8>,
+    ip_filter: IpFilter,
+    max_payload_size: usize,
+    // A Packet taken from the queue is held here until it has been sent, so
+    // that it isn't lost if `receive` is cancelled part way through sending
+    outgoing_packet: Option<Packet>,
+    metrics: ServerSocketMetrics,
 }
 
 impl ServerSocket {
-    /// Returns a new ServerSocket, listening at the given socket address
+    /// Returns a new ServerSocket, listening at the given socket address, or an
+    /// error if the socket could not be bound
     pub async fn listen(
         session_listen_addr: SocketAddr,
         _webrtc_listen_addr: SocketAddr,
         _public_webrtc_addr: SocketAddr,
-    ) -> Box<dyn ServerSocketTrait> {
-        let socket = Async::new(UdpSocket::bind(&session_listen_addr).unwrap()).unwrap();
+        config: ServerSocketConfig,
+    ) -> Result<Box<dyn ServerSocketTrait>, NaiaServerSocketError> {
+        let socket = UdpSocket::bind(&session_listen_addr)
+            .and_then(Async::new)
+            .map_err(|err| NaiaServerSocketError::Wrapped(Box::new(err)))?;
+        let local_addr = socket
+            .get_ref()
+            .local_addr()
+            .map_err(|err| NaiaServerSocketError::Wrapped(Box::new(err)))?;
+
+        let (to_client_sender, to_client_receiver) = mpsc::channel(config.send_queue_size);
 
-        let (to_client_sender, to_client_receiver) = mpsc::channel(CLIENT_CHANNEL_SIZE);
+        // Datagrams which don't fit in the buffer are silently truncated, so
+        // leave room for one more byte than is accepted to be able to tell
+        let max_payload_size = config.receive_buffer_size.min(config.max_payload_size);
 
-        Box::new(ServerSocket {
+        Ok(Box::new(ServerSocket {
             socket,
+            local_addr,
             to_client_sender,
             to_client_receiver,
-            receive_buffer: vec![0; 0x10000], /* Hopefully get rid of this one day.. next version
-                                               * of webrtc-unreliable should make that happen */
-        })
+            receive_buffer: vec![0; max_payload_size + 1],
+            ip_filter: config.ip_filter,
+            max_payload_size,
+            outgoing_packet: None,
+            metrics: ServerSocketMetrics::default(),
+        }))
+    }
+
+    // Checks a datagram which has been read into the receive buffer, returning
+    // None if it should be dropped without telling the caller
+    fn accept_datagram(
+        &mut self,
+        message_len: usize,
+        message_address: SocketAddr,
+    ) -> Option<Result<Packet, NaiaServerSocketError>> {
+        if !self.ip_filter.is_allowed(&message_address.ip()) {
+            self.metrics.packets_dropped += 1;
+            return None;
+        }
+        if message_len > self.max_payload_size {
+            self.metrics.packets_dropped += 1;
+            return Some(Err(NaiaServerSocketError::PayloadTooLarge(message_address)));
+        }
+        self.metrics.record_received(message_len);
+        let payload: Vec<u8> = self.receive_buffer[0..message_len]
+            .iter()
+            .cloned()
+            .collect();
+        Some(Ok(Packet::new_raw(
+            message_address,
+            payload.into_boxed_slice(),
+        )))
     }
 }
 
@@ -55,6 +105,23 @@ impl ServerSocketTrait for ServerSocket {
         }
 
         loop {
+            if let Some(packet) = &self.outgoing_packet {
+                let address = packet.address();
+                let payload_len = packet.payload().len();
+                let result = self.socket.send_to(packet.payload(), address).await;
+                self.outgoing_packet = None;
+
+                match result {
+                    Err(_) => {
+                        self.metrics.send_errors += 1;
+                        return Err(NaiaServerSocketError::SendError(address));
+                    }
+                    _ => {
+                        self.metrics.record_sent(payload_len);
+                    }
+                }
+            }
+
             let next = {
                 let to_client_receiver_next = self.to_client_receiver.next().fuse();
                 pin_mut!(to_client_receiver_next);
@@ -79,34 +146,94 @@ impl ServerSocketTrait for ServerSocket {
             match next {
                 Next::FromClientMessage(from_client_message) => match from_client_message {
                     Ok((message_len, message_address)) => {
-                        let payload: Vec<u8> = self.receive_buffer[0..message_len]
-                            .iter()
-                            .cloned()
-                            .collect();
-                        return Ok(Packet::new_raw(message_address, payload.into_boxed_slice()));
+                        if let Some(result) = self.accept_datagram(message_len, message_address) {
+                            return result;
+                        }
                     }
                     Err(err) => {
+                        self.metrics.receive_errors += 1;
                         return Err(NaiaServerSocketError::Wrapped(Box::new(err)));
                     }
                 },
                 Next::ToClientMessage(packet) => {
-                    let address = packet.address();
+                    self.outgoing_packet = Some(packet);
+                }
+            }
+        }
+    }
 
-                    match self.socket.send_to(packet.payload(), address).await {
-                        Err(_) => {
-                            return Err(NaiaServerSocketError::SendError(address));
-                        }
-                        _ => {}
+    async fn receive_batch(&mut self, max: usize) -> Vec<Result<Packet, NaiaServerSocketError>> {
+        let mut results = Vec::new();
+        if max == 0 {
+            return results;
+        }
+
+        results.push(self.receive().await);
+
+        // The underlying socket is non-blocking, so it can be read from
+        // directly until it has nothing more to give
+        while results.len() < max {
+            match self.socket.get_ref().recv_from(&mut self.receive_buffer) {
+                Ok((message_len, message_address)) => {
+                    if let Some(result) = self.accept_datagram(message_len, message_address) {
+                        results.push(result);
                     }
                 }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    self.metrics.receive_errors += 1;
+                    results.push(Err(NaiaServerSocketError::Wrapped(Box::new(err))));
+                    break;
+                }
             }
         }
+
+        results
     }
 
     fn get_sender(&mut self) -> MessageSender {
         return MessageSender::new(self.to_client_sender.clone());
     }
 
+    fn session_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn webrtc_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn connected_clients(&self) -> Vec<SocketAddr> {
+        Vec::new()
+    }
+
+    fn metrics(&self) -> ServerSocketMetrics {
+        self.metrics
+    }
+
+    async fn broadcast(&mut self, _payload: &[u8]) {}
+
+    async fn disconnect(&mut self, _address: &SocketAddr) -> bool {
+        false
+    }
+
+    async fn shutdown(mut self: Box<Self>) {
+        if let Some(packet) = self.outgoing_packet.take() {
+            let _ = self
+                .socket
+                .send_to(packet.payload(), packet.address())
+                .await;
+        }
+        // Only what is queued already is sent, MessageSenders may still be in use
+        self.to_client_receiver.close();
+        while let Ok(packet) = self.to_client_receiver.try_recv() {
+            let _ = self
+                .socket
+                .send_to(packet.payload(), packet.address())
+                .await;
+        }
+    }
+
     fn with_link_conditioner(
         self: Box<Self>,
         config: &LinkConditionerConfig,
@@ -114,3 +241,72 @@ impl ServerSocketTrait for ServerSocket {
         Box::new(LinkConditioner::new(config, self))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use futures_util::FutureExt;
+
+    use super::ServerSocket;
+    use crate::{Packet, ServerSocketConfig};
+
+    const PACKET_COUNT: u8 = 16;
+
+    #[test]
+    fn dropping_receive_loses_no_packets() {
+        let listen_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let mut socket = async_io::block_on(ServerSocket::listen(
+            listen_addr,
+            listen_addr,
+            listen_addr,
+            ServerSocketConfig::default(),
+        ))
+        .unwrap();
+        let mut sender = socket.get_sender();
+        let server_addr = socket.session_addr();
+
+        let client = UdpSocket::bind(listen_addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        // Nothing is available yet, so this future is dropped while pending
+        assert!(socket.receive().now_or_never().is_none());
+
+        for index in 0..PACKET_COUNT {
+            sender
+                .try_send(Packet::new(client_addr, vec![index]))
+                .unwrap();
+            client.send_to(&[index], server_addr).unwrap();
+        }
+
+        // Each future is polled once and dropped, whether or not it is done
+        let mut received = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while received.len() < PACKET_COUNT as usize && Instant::now() < deadline {
+            match socket.receive().now_or_never() {
+                Some(result) => received.push(result.unwrap().payload()[0]),
+                None => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        assert_eq!(received, (0..PACKET_COUNT).collect::<Vec<u8>>());
+
+        // Anything still queued is sent before the next future pends
+        assert!(socket.receive().now_or_never().is_none());
+
+        let mut buffer = [0; 16];
+        let mut sent = Vec::new();
+        for _ in 0..PACKET_COUNT {
+            let (len, address) = client.recv_from(&mut buffer).unwrap();
+            assert_eq!((len, address), (1, server_addr));
+            sent.push(buffer[0]);
+        }
+        assert_eq!(sent, (0..PACKET_COUNT).collect::<Vec<u8>>());
+    }
+}
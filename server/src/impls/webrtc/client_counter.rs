@@ -0,0 +1,72 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Matches how long webrtc-unreliable keeps a granted session around while
+// waiting for the client to start DTLS
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Counts clients towards the configured maximum, including sessions which
+/// have been granted over HTTP but have not yet turned into a client of the
+/// RTC server
+#[derive(Debug)]
+pub struct ClientCounter {
+    counts: Mutex<Counts>,
+}
+
+#[derive(Debug)]
+struct Counts {
+    active: usize,
+    pending: VecDeque<Instant>,
+}
+
+impl ClientCounter {
+    pub fn new() -> Self {
+        ClientCounter {
+            counts: Mutex::new(Counts {
+                active: 0,
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Reserves a place for a new session, returning false if the server is
+    /// already full
+    pub fn try_reserve(&self, max_clients: usize) -> bool {
+        let now = Instant::now();
+        let mut counts = self.counts.lock().expect("client counter poisoned");
+
+        while let Some(granted) = counts.pending.front() {
+            if now.duration_since(*granted) < SESSION_TIMEOUT {
+                break;
+            }
+            counts.pending.pop_front();
+        }
+
+        if counts.active + counts.pending.len() >= max_clients {
+            return false;
+        }
+
+        counts.pending.push_back(now);
+        true
+    }
+
+    /// Gives back a place reserved with `try_reserve`, for a session which
+    /// ended up not being granted
+    pub fn release(&self) {
+        let mut counts = self.counts.lock().expect("client counter poisoned");
+        counts.pending.pop_back();
+    }
+
+    /// Updates the number of clients the RTC server has. Any new clients are
+    /// taken to have come from the oldest pending sessions
+    pub fn set_active(&self, active: usize) {
+        let mut counts = self.counts.lock().expect("client counter poisoned");
+        for _ in counts.active..active {
+            counts.pending.pop_front();
+        }
+        counts.active = active;
+    }
+}
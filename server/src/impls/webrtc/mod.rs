@@ -1,2 +1,4 @@
+mod client_counter;
+mod rate_limiter;
 pub mod server_socket;
 mod session;
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use crate::SessionRateLimit;
+
+// How often buckets which have refilled completely are forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Bucket {
+    tokens: f32,
+    last_update: Instant,
+}
+
+/// Tracks a token bucket per source IP address, to decide whether a new
+/// session request from that address should be served
+pub struct SessionRateLimiter {
+    config: SessionRateLimit,
+    buckets: HashMap<IpAddr, Bucket>,
+    last_sweep: Instant,
+}
+
+impl SessionRateLimiter {
+    pub fn new(config: SessionRateLimit) -> Self {
+        SessionRateLimiter {
+            config,
+            buckets: HashMap::new(),
+            last_sweep: Instant::now(),
+        }
+    }
+
+    /// Returns whether a request from the given address is allowed, consuming
+    /// a token from its bucket if so
+    pub fn allow(&mut self, address: &IpAddr) -> bool {
+        if self
+            .config
+            .exempt
+            .iter()
+            .any(|range| range.contains(address))
+        {
+            return true;
+        }
+
+        let now = Instant::now();
+        let burst = self.config.burst as f32;
+        let refill_rate = self.config.requests_per_second;
+
+        // Buckets which have refilled completely behave exactly like new ones,
+        // so forget them to keep the map from growing without bound
+        if now.duration_since(self.last_sweep) >= SWEEP_INTERVAL {
+            self.buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.last_update).as_secs_f32();
+                bucket.tokens + (elapsed * refill_rate) < burst
+            });
+            self.last_sweep = now;
+        }
+
+        let bucket = self.buckets.entry(bucket_key(address)).or_insert(Bucket {
+            tokens: burst,
+            last_update: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_update).as_secs_f32();
+        bucket.tokens = (bucket.tokens + (elapsed * refill_rate)).min(burst);
+        bucket.last_update = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// A single IPv6 client is usually handed a whole /64, so addresses within one
+// share a bucket, or a client could dodge the limit by rotating through them
+fn bucket_key(address: &IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V4(_) => *address,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(*v6) & (!0 << 64))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::SessionRateLimiter;
+    use crate::SessionRateLimit;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn burst_is_allowed_then_limited() {
+        let mut limiter = SessionRateLimiter::new(SessionRateLimit::new(2, 0.0));
+        assert!(limiter.allow(&ip("10.0.0.1")));
+        assert!(limiter.allow(&ip("10.0.0.1")));
+        assert!(!limiter.allow(&ip("10.0.0.1")));
+        assert!(limiter.allow(&ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn ipv6_addresses_share_a_bucket_per_64() {
+        let mut limiter = SessionRateLimiter::new(SessionRateLimit::new(1, 0.0));
+        assert!(limiter.allow(&ip("2001:db8:0:1::1")));
+        assert!(!limiter.allow(&ip("2001:db8:0:1::2")));
+        assert!(!limiter.allow(&ip("2001:db8:0:1:ffff:ffff:ffff:ffff")));
+        assert!(limiter.allow(&ip("2001:db8:0:2::1")));
+    }
+
+    #[test]
+    fn mapped_addresses_share_a_bucket_with_ipv4() {
+        let mut limiter = SessionRateLimiter::new(SessionRateLimit::new(1, 0.0));
+        assert!(limiter.allow(&ip("10.0.0.1")));
+        assert!(!limiter.allow(&ip("::ffff:10.0.0.1")));
+    }
+}
@@ -1,4 +1,9 @@
-use std::{io::Error as IoError, net::SocketAddr};
+use std::{
+    collections::HashSet,
+    io::{Error as IoError, ErrorKind},
+    net::SocketAddr,
+    sync::Arc,
+};
 
 use async_trait::async_trait;
 
@@ -8,47 +13,90 @@ use webrtc_unreliable::{
 
 use futures_channel::mpsc;
 use futures_util::{pin_mut, select, FutureExt, StreamExt};
+use log::warn;
+use smol::Task;
 
 use naia_socket_shared::LinkConditionerConfig;
 
-use super::session::start_session_server;
+use super::{client_counter::ClientCounter, session::start_session_server};
 
 use crate::{
     error::NaiaServerSocketError, link_conditioner::LinkConditioner, message_sender::MessageSender,
-    Packet, ServerSocketTrait,
+    IpFilter, Packet, ServerSocketConfig, ServerSocketMetrics, ServerSocketTrait,
 };
 
-const CLIENT_CHANNEL_SIZE: usize = 8;
-
 /// A socket server which communicates with clients using an underlying
 /// unordered & unreliable network protocol
 #[derive(Debug)]
 pub struct ServerSocket {
     rtc_server: RtcServer,
+    session_addr: SocketAddr,
+    // webrtc-unreliable doesn't report the address it ends up bound to, so this
+    // is the listen address it was given, which `listen` requires a port for
+    webrtc_addr: SocketAddr,
     to_client_sender: mpsc::Sender<Packet>,
     to_client_receiver: mpsc::Receiver<Packet>,
+    ip_filter: IpFilter,
+    max_payload_size: usize,
+    // Shared with the session server, which turns requests away once this
+    // reaches the configured maximum. Only kept up to date when one is set
+    client_counter: Option<Arc<ClientCounter>>,
+    // A Packet taken from the queue is held here until it has been sent, so
+    // that it isn't lost if `receive` is cancelled part way through sending
+    outgoing_packet: Option<Packet>,
+    // Clients disconnected while Packets for them may still be queued
+    disconnected: HashSet<SocketAddr>,
+    // Dropping this cancels the session server, which releases its port
+    session_server: Task<()>,
 }
 
 impl ServerSocket {
-    /// Returns a new ServerSocket, listening at the given socket address
+    /// Returns a new ServerSocket, listening at the given socket addresses, or
+    /// an error if either the session server or the RTC server could not be
+    /// started. The WebRTC listen address must give a port other than 0, as
+    /// the port the RTC server ends up bound to could not be reported
     pub async fn listen(
         session_listen_addr: SocketAddr,
         webrtc_listen_addr: SocketAddr,
         public_webrtc_addr: SocketAddr,
-    ) -> Box<dyn ServerSocketTrait> {
-        let (to_client_sender, to_client_receiver) = mpsc::channel(CLIENT_CHANNEL_SIZE);
+        config: ServerSocketConfig,
+    ) -> Result<Box<dyn ServerSocketTrait>, NaiaServerSocketError> {
+        if webrtc_listen_addr.port() == 0 {
+            return Err(NaiaServerSocketError::Wrapped(Box::new(IoError::new(
+                ErrorKind::InvalidInput,
+                "the WebRTC listen address must not use port 0",
+            ))));
+        }
 
-        let rtc_server = RtcServer::new(webrtc_listen_addr, public_webrtc_addr).await;
+        let (to_client_sender, to_client_receiver) = mpsc::channel(config.send_queue_size);
 
-        let socket = ServerSocket {
+        let rtc_server = RtcServer::new(webrtc_listen_addr, public_webrtc_addr)
+            .await
+            .map_err(|err| NaiaServerSocketError::Wrapped(Box::new(err)))?;
+
+        let client_counter = Arc::new(ClientCounter::new());
+
+        let (session_server, session_addr) = start_session_server(
+            session_listen_addr,
+            rtc_server.session_endpoint(),
+            config.clone(),
+            client_counter.clone(),
+        )
+        .map_err(|err| NaiaServerSocketError::Wrapped(Box::new(err)))?;
+
+        Ok(Box::new(ServerSocket {
             rtc_server,
+            session_addr,
+            webrtc_addr: webrtc_listen_addr,
             to_client_sender,
             to_client_receiver,
-        };
-
-        start_session_server(session_listen_addr, socket.rtc_server.session_endpoint());
-
-        Box::new(socket)
+            ip_filter: config.ip_filter,
+            max_payload_size: config.max_payload_size,
+            client_counter: config.max_clients.map(|_| client_counter),
+            outgoing_packet: None,
+            disconnected: HashSet::new(),
+            session_server,
+        }))
     }
 }
 
@@ -61,6 +109,33 @@ impl ServerSocketTrait for ServerSocket {
         }
 
         loop {
+            if let Some(packet) = &self.outgoing_packet {
+                let address = packet.address();
+                let result = self
+                    .rtc_server
+                    .send(packet.payload(), MessageType::Binary, &address)
+                    .await;
+                self.outgoing_packet = None;
+
+                if result.is_err() {
+                    return Err(NaiaServerSocketError::SendError(address));
+                }
+            }
+
+            // Packets queued before their client was disconnected are skipped.
+            // Once the queue has been emptied there can't be any left
+            if !self.disconnected.is_empty() {
+                match self.to_client_receiver.try_recv() {
+                    Ok(packet) => {
+                        if !self.disconnected.contains(&packet.address()) {
+                            self.outgoing_packet = Some(packet);
+                        }
+                        continue;
+                    }
+                    Err(_) => self.disconnected.clear(),
+                }
+            }
+
             let next = {
                 let to_client_receiver_next = self.to_client_receiver.next().fuse();
                 pin_mut!(to_client_receiver_next);
@@ -88,9 +163,31 @@ impl ServerSocketTrait for ServerSocket {
                 }
             };
 
+            if let Some(client_counter) = &self.client_counter {
+                client_counter.set_active(self.rtc_server.active_clients());
+            }
+
             match next {
                 Next::FromClientMessage(from_client_message) => match from_client_message {
                     Ok(packet) => {
+                        // The datagram has already been decrypted by the RTC server at this
+                        // point, but a disallowed address should normally never get past the
+                        // session request in the first place
+                        if !self.ip_filter.is_allowed(&packet.address().ip()) {
+                            self.rtc_server.metrics.packets_dropped += 1;
+                            continue;
+                        }
+                        if packet.payload().len() > self.max_payload_size {
+                            self.rtc_server.metrics.packets_dropped += 1;
+                            let address = packet.address();
+                            if let Err(err) = self.rtc_server.disconnect(&address).await {
+                                warn!("could not disconnect client {}: {}", address, err);
+                            }
+                            return Err(NaiaServerSocketError::PayloadTooLarge(address));
+                        }
+                        self.rtc_server
+                            .metrics
+                            .record_received(packet.payload().len());
                         return Ok(packet);
                     }
                     Err(err) => {
@@ -98,18 +195,7 @@ impl ServerSocketTrait for ServerSocket {
                     }
                 },
                 Next::ToClientMessage(packet) => {
-                    let address = packet.address();
-
-                    match self
-                        .rtc_server
-                        .send(packet.payload(), MessageType::Binary, &address)
-                        .await
-                    {
-                        Err(_) => {
-                            return Err(NaiaServerSocketError::SendError(address));
-                        }
-                        _ => {}
-                    }
+                    self.outgoing_packet = Some(packet);
                 }
             }
         }
@@ -119,6 +205,95 @@ impl ServerSocketTrait for ServerSocket {
         return MessageSender::new(self.to_client_sender.clone());
     }
 
+    fn session_addr(&self) -> SocketAddr {
+        self.session_addr
+    }
+
+    fn webrtc_addr(&self) -> SocketAddr {
+        self.webrtc_addr
+    }
+
+    fn connected_clients(&self) -> Vec<SocketAddr> {
+        self.rtc_server.connected_clients()
+    }
+
+    fn metrics(&self) -> ServerSocketMetrics {
+        let mut metrics = self.rtc_server.metrics;
+        metrics.connected_clients = self.rtc_server.connected_clients().len();
+        metrics.handshaking_clients = self
+            .rtc_server
+            .active_clients()
+            .saturating_sub(metrics.connected_clients);
+        metrics
+    }
+
+    async fn broadcast(&mut self, payload: &[u8]) {
+        for address in self.rtc_server.connected_clients() {
+            let _ = self
+                .rtc_server
+                .send(payload, MessageType::Binary, &address)
+                .await;
+        }
+    }
+
+    async fn disconnect(&mut self, address: &SocketAddr) -> bool {
+        // webrtc-unreliable quietly ignores addresses it doesn't know about, so
+        // check first to be able to report whether the client was connected
+        if !self.rtc_server.is_connected(address) {
+            return false;
+        }
+
+        // The client's shutdown has begun even if the abort could not be sent,
+        // so it is no longer connected either way
+        if let Err(err) = self.rtc_server.disconnect(address).await {
+            warn!("could not disconnect client {}: {}", address, err);
+        }
+
+        // Anything still queued for the client is dropped by `receive` as it
+        // comes up, rather than failing to send
+        if let Some(packet) = &self.outgoing_packet {
+            if packet.address() == *address {
+                self.outgoing_packet = None;
+            }
+        }
+        self.disconnected.insert(*address);
+
+        true
+    }
+
+    async fn shutdown(self: Box<Self>) {
+        let ServerSocket {
+            mut rtc_server,
+            mut to_client_receiver,
+            session_server,
+            outgoing_packet,
+            ..
+        } = *self;
+
+        session_server.cancel().await;
+
+        if let Some(packet) = outgoing_packet {
+            let _ = rtc_server
+                .send(packet.payload(), MessageType::Binary, &packet.address())
+                .await;
+        }
+
+        // Stop accepting new Packets first, so that senders which are still
+        // around can't keep this from ever finishing
+        to_client_receiver.close();
+        while let Ok(packet) = to_client_receiver.try_recv() {
+            let _ = rtc_server
+                .send(packet.payload(), MessageType::Binary, &packet.address())
+                .await;
+        }
+
+        for address in rtc_server.connected_clients() {
+            if let Err(err) = rtc_server.disconnect(&address).await {
+                warn!("could not disconnect client {}: {}", address, err);
+            }
+        }
+    }
+
     fn with_link_conditioner(
         self: Box<Self>,
         config: &LinkConditionerConfig,
@@ -129,15 +304,20 @@ impl ServerSocketTrait for ServerSocket {
 
 struct RtcServer {
     inner: InnerRtcServer,
+    metrics: ServerSocketMetrics,
 }
 
 impl RtcServer {
-    pub async fn new(listen_addr: SocketAddr, public_address: SocketAddr) -> RtcServer {
-        let inner = InnerRtcServer::new(listen_addr, public_address)
-            .await
-            .expect("could not start RTC server");
+    pub async fn new(
+        listen_addr: SocketAddr,
+        public_address: SocketAddr,
+    ) -> Result<RtcServer, IoError> {
+        let inner = InnerRtcServer::new(listen_addr, public_address).await?;
 
-        return RtcServer { inner };
+        return Ok(RtcServer {
+            inner,
+            metrics: ServerSocketMetrics::default(),
+        });
     }
 
     pub fn session_endpoint(&self) -> SessionEndpoint {
@@ -145,7 +325,11 @@ impl RtcServer {
     }
 
     pub async fn recv(&mut self) -> Result<MessageResult<'_>, IoError> {
-        self.inner.recv().await
+        let result = self.inner.recv().await;
+        if result.is_err() {
+            self.metrics.receive_errors += 1;
+        }
+        result
     }
 
     pub async fn send(
@@ -154,7 +338,32 @@ impl RtcServer {
         message_type: MessageType,
         remote_addr: &SocketAddr,
     ) -> Result<(), SendError> {
-        self.inner.send(message, message_type, remote_addr).await
+        let result = self.inner.send(message, message_type, remote_addr).await;
+        match &result {
+            Ok(_) => self.metrics.record_sent(message.len()),
+            Err(_) => self.metrics.send_errors += 1,
+        }
+        result
+    }
+
+    pub fn active_clients(&self) -> usize {
+        self.inner.active_clients()
+    }
+
+    pub fn connected_clients(&self) -> Vec<SocketAddr> {
+        self.inner.connected_clients().cloned().collect()
+    }
+
+    pub fn is_connected(&self, remote_addr: &SocketAddr) -> bool {
+        self.inner.is_connected(remote_addr)
+    }
+
+    pub async fn disconnect(&mut self, remote_addr: &SocketAddr) -> Result<(), IoError> {
+        let result = self.inner.disconnect(remote_addr).await;
+        if result.is_err() {
+            self.metrics.send_errors += 1;
+        }
+        result
     }
 }
 
@@ -1,7 +1,9 @@
 use std::{
-    net::{SocketAddr, TcpListener, TcpStream},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
     pin::Pin,
+    sync::Mutex,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use futures_core::Stream;
@@ -13,48 +15,110 @@ use http::{header, HeaderValue, Response};
 use smol::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
     prelude::*,
-    Async,
+    Async, Task, Timer,
 };
 
-use log::info;
+use log::{info, warn};
 
 use webrtc_unreliable::SessionEndpoint;
 
-pub fn start_session_server(socket_address: SocketAddr, session_endpoint: SessionEndpoint) {
-    smol::spawn(async move {
-        listen(
-            session_endpoint.clone(),
-            Async::<TcpListener>::bind(socket_address).unwrap(),
-        )
-        .await;
-    })
-    .detach();
+use super::{client_counter::ClientCounter, rate_limiter::SessionRateLimiter};
+use crate::ServerSocketConfig;
+
+const STATUS_NOT_FOUND: &str = "404 NOT FOUND";
+const STATUS_TOO_MANY_REQUESTS: &str = "429 TOO MANY REQUESTS";
+const STATUS_SERVICE_UNAVAILABLE: &str = "503 SERVICE UNAVAILABLE";
+
+const BODY_SERVER_FULL: &str = "server full";
+
+// How long to wait before accepting again after failing to accept
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// How long to wait for the rest of a request which is being turned away
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn start_session_server(
+    socket_address: SocketAddr,
+    session_endpoint: SessionEndpoint,
+    config: ServerSocketConfig,
+    client_counter: std::sync::Arc<ClientCounter>,
+) -> Result<(Task<()>, SocketAddr), std::io::Error> {
+    let listener = Async::<TcpListener>::bind(socket_address)?;
+    let local_addr = listener.get_ref().local_addr()?;
+
+    let task = smol::spawn(async move {
+        listen(session_endpoint.clone(), listener, config, client_counter).await;
+    });
+
+    Ok((task, local_addr))
 }
 
 /// Listens for incoming connections and serves them.
-async fn listen(session_endpoint: SessionEndpoint, listener: Async<TcpListener>) {
+async fn listen(
+    session_endpoint: SessionEndpoint,
+    listener: Async<TcpListener>,
+    config: ServerSocketConfig,
+    client_counter: std::sync::Arc<ClientCounter>,
+) {
     info!(
         "Session initiator listening on http://{}",
         listener.get_ref().local_addr().unwrap()
     );
 
+    let rate_limiter = config
+        .session_rate_limit
+        .clone()
+        .map(|limit| std::sync::Arc::new(Mutex::new(SessionRateLimiter::new(limit))));
+
     loop {
         // Accept the next connection.
-        let (response_stream, _) = listener.accept().await.unwrap();
+        let (response_stream, remote_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                warn!("could not accept session connection: {}", err);
+                // Errors such as running out of file descriptors tend to
+                // persist, so don't retry straight away
+                Timer::after(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        if !config.ip_filter.is_allowed(&remote_addr.ip()) {
+            info!("Rejected WebRTC session request from {}", remote_addr);
+            continue;
+        }
 
         let session_endpoint_clone = session_endpoint.clone();
+        let config_clone = config.clone();
+        let rate_limiter_clone = rate_limiter.clone();
+        let client_counter_clone = client_counter.clone();
 
         // Spawn a background task serving this connection.
         smol::spawn(async move {
-            serve(session_endpoint_clone, Arc::new(response_stream)).await;
+            serve(
+                session_endpoint_clone,
+                Arc::new(response_stream),
+                remote_addr,
+                config_clone,
+                rate_limiter_clone,
+                client_counter_clone,
+            )
+            .await;
         })
         .detach();
     }
 }
 
 /// Reads a request from the client and sends it a response.
-async fn serve(mut session_endpoint: SessionEndpoint, mut stream: Arc<Async<TcpStream>>) {
-    let remote_addr = stream.get_ref().local_addr().unwrap();
+async fn serve(
+    mut session_endpoint: SessionEndpoint,
+    mut stream: Arc<Async<TcpStream>>,
+    remote_addr: SocketAddr,
+    config: ServerSocketConfig,
+    rate_limiter: Option<std::sync::Arc<Mutex<SessionRateLimiter>>>,
+    client_counter: std::sync::Arc<ClientCounter>,
+) {
+    let mut request_origin: Option<String> = None;
     let mut success: bool = false;
 
     {
@@ -63,19 +127,72 @@ async fn serve(mut session_endpoint: SessionEndpoint, mut stream: Arc<Async<TcpS
         {
             if let Some(line) = lines.next().await {
                 let line = line.unwrap();
-                if line.starts_with("POST /new_rtc_session") {
+                if is_session_request(&line, &config.session_path) {
                     while let Some(line) = lines.next().await {
                         let line = line.unwrap();
                         if line.len() == 0 {
                             success = true;
                             break;
                         }
+                        if let Some(origin) = header_value(&line, "origin") {
+                            request_origin = Some(origin.to_string());
+                        }
                     }
                 }
             }
         }
 
+        let allow_origin = config
+            .allowed_origins
+            .allow_origin(request_origin.as_deref());
+        let vary_origin = config.allowed_origins.varies_by_origin();
+
         if success {
+            // Only checked once the request has been read, so that the
+            // response can carry the CORS header the browser needs to read it
+            if let Some(rate_limiter) = rate_limiter {
+                let allowed = rate_limiter
+                    .lock()
+                    .expect("session rate limiter poisoned")
+                    .allow(&remote_addr.ip());
+                if !allowed {
+                    info!("Rate limited WebRTC session request from {}", remote_addr);
+                    respond(
+                        stream,
+                        STATUS_TOO_MANY_REQUESTS,
+                        "",
+                        allow_origin.as_deref(),
+                        vary_origin,
+                    )
+                    .await;
+                    return;
+                }
+            }
+
+            // A place is reserved for the session as soon as it is granted, as
+            // the RTC server only counts it once the client starts DTLS
+            let reserved = match config.max_clients {
+                Some(max_clients) => {
+                    if !client_counter.try_reserve(max_clients) {
+                        info!(
+                            "Server full, rejected WebRTC session request from {}",
+                            remote_addr
+                        );
+                        respond(
+                            stream,
+                            STATUS_SERVICE_UNAVAILABLE,
+                            BODY_SERVER_FULL,
+                            allow_origin.as_deref(),
+                            vary_origin,
+                        )
+                        .await;
+                        return;
+                    }
+                    true
+                }
+                None => false,
+            };
+
             success = false;
 
             let buf = RequestBuffer::new(&mut lines);
@@ -84,39 +201,143 @@ async fn serve(mut session_endpoint: SessionEndpoint, mut stream: Arc<Async<TcpS
                 Ok(mut resp) => {
                     success = true;
 
-                    resp.headers_mut().insert(
-                        header::ACCESS_CONTROL_ALLOW_ORIGIN,
-                        HeaderValue::from_static("*"),
-                    );
+                    if let Some(value) = allow_origin
+                        .as_ref()
+                        .and_then(|origin| HeaderValue::from_str(origin).ok())
+                    {
+                        resp.headers_mut()
+                            .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+                    }
+                    if vary_origin {
+                        resp.headers_mut()
+                            .insert(header::VARY, HeaderValue::from_static("Origin"));
+                    }
 
                     let mut out = response_header_to_vec(&resp);
                     out.extend_from_slice(resp.body().as_bytes());
 
                     info!("WebRTC session request from {}", remote_addr);
 
+                    if config.log_session_answers {
+                        info!("WebRTC session answer to {}: {}", remote_addr, resp.body());
+                    }
+
                     stream.write_all(&out).await.unwrap();
                 }
                 Err(err) => {
                     info!("error: {}", err);
+                    if reserved {
+                        client_counter.release();
+                    }
                 }
             }
         }
     }
 
     if !success {
-        stream.write_all(RESPONSE_BAD).await.unwrap();
+        let allow_origin = config
+            .allowed_origins
+            .allow_origin(request_origin.as_deref());
+        respond(
+            stream,
+            STATUS_NOT_FOUND,
+            "",
+            allow_origin.as_deref(),
+            config.allowed_origins.varies_by_origin(),
+        )
+        .await;
+        return;
     }
 
     stream.flush().await.unwrap();
     stream.close().await.unwrap();
 }
 
-const RESPONSE_BAD: &[u8] = br#"
-HTTP/1.1 404 NOT FOUND
-Content-Type: text/html
-Content-Length: 0
-Access-Control-Allow-Origin: *
-"#;
+/// Sends a response with the given status and body, then closes the stream
+async fn respond(
+    mut stream: Arc<Async<TcpStream>>,
+    status: &str,
+    body: &str,
+    allow_origin: Option<&str>,
+    vary_origin: bool,
+) {
+    let _ = stream
+        .write_all(&response_with_body(status, body, allow_origin, vary_origin))
+        .await;
+    let _ = stream.flush().await;
+
+    // Closing a socket with unread request bytes makes the OS reset the
+    // connection, which can stop the client from ever seeing the response. So
+    // end the response, then read whatever is left of the request first
+    let _ = stream.get_ref().shutdown(Shutdown::Write);
+    drain(&mut stream).await;
+
+    let _ = stream.close().await;
+}
+
+/// Reads and discards from the stream until the client closes its side, or
+/// until DRAIN_TIMEOUT has passed
+async fn drain(stream: &mut Arc<Async<TcpStream>>) {
+    let mut buf = [0; 1024];
+    let read_to_end = async {
+        while let Ok(read_len) = stream.read(&mut buf).await {
+            if read_len == 0 {
+                break;
+            }
+        }
+    };
+    let timeout = async {
+        Timer::after(DRAIN_TIMEOUT).await;
+    };
+    read_to_end.or(timeout).await;
+}
+
+fn response_with_body(
+    status: &str,
+    body: &str,
+    allow_origin: Option<&str>,
+    vary_origin: bool,
+) -> Vec<u8> {
+    let mut out = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\n",
+        status,
+        body.len()
+    )
+    .into_bytes();
+    if let Some(origin) = allow_origin {
+        out.extend_from_slice(format!("Access-Control-Allow-Origin: {}\r\n", origin).as_bytes());
+    }
+    if vary_origin {
+        out.extend_from_slice(b"Vary: Origin\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(body.as_bytes());
+    out
+}
+
+/// Returns whether a request line is a `POST` to the given path, ignoring any
+/// query string
+fn is_session_request(line: &str, session_path: &str) -> bool {
+    let mut split = line.split(' ');
+    if split.next() != Some("POST") {
+        return false;
+    }
+    match split.next() {
+        Some(target) => target.split('?').next() == Some(session_path),
+        None => false,
+    }
+}
+
+/// Returns the value of a request header line, if it is the named header
+fn header_value<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let mut split = line.splitn(2, ':');
+    let header_name = split.next()?;
+    if header_name.trim().eq_ignore_ascii_case(name) {
+        split.next().map(|value| value.trim())
+    } else {
+        None
+    }
+}
 
 struct RequestBuffer<'a, R: AsyncBufRead + Unpin> {
     buffer: &'a mut Lines<R>,
@@ -207,3 +428,25 @@ fn write_response_header<T>(
     w!(b"\r\n");
     Ok(len)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::is_session_request;
+
+    #[test]
+    fn session_request_ignores_query_string() {
+        let path = "/new_rtc_session";
+        assert!(is_session_request("POST /new_rtc_session HTTP/1.1", path));
+        assert!(is_session_request(
+            "POST /new_rtc_session?x=1 HTTP/1.1",
+            path
+        ));
+        assert!(!is_session_request("GET /new_rtc_session HTTP/1.1", path));
+        assert!(!is_session_request(
+            "POST /new_rtc_session/x HTTP/1.1",
+            path
+        ));
+        assert!(!is_session_request("POST /other HTTP/1.1", path));
+        assert!(!is_session_request("POST", path));
+    }
+}
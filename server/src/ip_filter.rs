@@ -0,0 +1,185 @@
+use std::net::IpAddr;
+
+/// A range of IP addresses, described in CIDR notation by a base address and
+/// the number of leading bits which must match it
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct IpRange {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Create a new range from a base address and a prefix length, for
+    /// example `10.0.0.0` & `8` for `10.0.0.0/8`. The prefix length is clamped
+    /// to the size of the address. IPv4-mapped IPv6 ranges, such as
+    /// `::ffff:10.0.0.0/104`, are treated as the IPv4 range they map to
+    pub fn new(address: IpAddr, prefix_len: u8) -> IpRange {
+        let (address, prefix_len) = match address {
+            IpAddr::V4(v4) => (IpAddr::V4(v4), prefix_len.min(32)),
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) if prefix_len >= 96 => (IpAddr::V4(v4), prefix_len.min(128) - 96),
+                _ => (IpAddr::V6(v6), prefix_len.min(128)),
+            },
+        };
+        IpRange {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// Create a range containing only the given address
+    pub fn single(address: IpAddr) -> IpRange {
+        IpRange::new(address, 128)
+    }
+
+    /// Returns whether the given address falls within this range. IPv4
+    /// clients of a dual-stack socket show up as IPv4-mapped IPv6 addresses,
+    /// so those are matched as the IPv4 address they map to
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.address, unmap(address)) {
+            (IpAddr::V4(base), IpAddr::V4(other)) => {
+                let mask: u32 = if self.prefix_len == 0 {
+                    0
+                } else {
+                    !0 << (32 - self.prefix_len as u32)
+                };
+                (u32::from(base) & mask) == (u32::from(other) & mask)
+            }
+            (IpAddr::V6(base), IpAddr::V6(other)) => {
+                let mask: u128 = if self.prefix_len == 0 {
+                    0
+                } else {
+                    !0 << (128 - self.prefix_len as u32)
+                };
+                (u128::from(base) & mask) == (u128::from(other) & mask)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn unmap(address: &IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(*v6),
+        },
+        IpAddr::V4(v4) => IpAddr::V4(*v4),
+    }
+}
+
+/// Decides which source IP addresses the Server Socket will accept traffic
+/// from. An address is rejected if it falls within any denied range, or if
+/// any allowed ranges are given and it falls within none of them
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    /// Ranges of addresses which are accepted. If empty, all addresses not
+    /// otherwise denied are accepted
+    pub allow: Vec<IpRange>,
+    /// Ranges of addresses which are always rejected
+    pub deny: Vec<IpRange>,
+}
+
+impl IpFilter {
+    /// Creates a new IpFilter
+    pub fn new(allow: Vec<IpRange>, deny: Vec<IpRange>) -> Self {
+        IpFilter { allow, deny }
+    }
+
+    /// Returns whether traffic from the given address should be accepted
+    pub fn is_allowed(&self, address: &IpAddr) -> bool {
+        if self.deny.iter().any(|range| range.contains(address)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{IpFilter, IpRange};
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn zero_prefix_contains_whole_family() {
+        let v4 = IpRange::new(ip("10.1.2.3"), 0);
+        assert!(v4.contains(&ip("0.0.0.0")));
+        assert!(v4.contains(&ip("255.255.255.255")));
+        assert!(!v4.contains(&ip("2001:db8::1")));
+
+        let v6 = IpRange::new(ip("2001:db8::1"), 0);
+        assert!(v6.contains(&ip("::1")));
+        assert!(v6.contains(&ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+        assert!(!v6.contains(&ip("10.1.2.3")));
+    }
+
+    #[test]
+    fn prefix_matches_leading_bits() {
+        let range = IpRange::new(ip("192.168.1.0"), 23);
+        assert!(range.contains(&ip("192.168.0.1")));
+        assert!(range.contains(&ip("192.168.1.255")));
+        assert!(!range.contains(&ip("192.168.2.0")));
+
+        let range = IpRange::new(ip("2001:db8::"), 32);
+        assert!(range.contains(&ip("2001:db8:ffff::1")));
+        assert!(!range.contains(&ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn full_prefix_contains_single_address() {
+        let v4 = IpRange::new(ip("10.1.2.3"), 32);
+        assert!(v4.contains(&ip("10.1.2.3")));
+        assert!(!v4.contains(&ip("10.1.2.4")));
+
+        let v6 = IpRange::new(ip("2001:db8::1"), 128);
+        assert!(v6.contains(&ip("2001:db8::1")));
+        assert!(!v6.contains(&ip("2001:db8::2")));
+
+        assert_eq!(IpRange::single(ip("10.1.2.3")), v4);
+        assert_eq!(IpRange::new(ip("10.1.2.3"), 200), v4);
+    }
+
+    #[test]
+    fn mapped_addresses_match_ipv4_ranges() {
+        let v4 = IpRange::new(ip("10.0.0.0"), 8);
+        assert!(v4.contains(&ip("::ffff:10.1.2.3")));
+        assert!(!v4.contains(&ip("::ffff:11.1.2.3")));
+
+        let mapped = IpRange::new(ip("::ffff:10.0.0.0"), 104);
+        assert_eq!(mapped, v4);
+        assert!(mapped.contains(&ip("10.1.2.3")));
+        assert!(mapped.contains(&ip("::ffff:10.1.2.3")));
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let filter = IpFilter::new(
+            vec![IpRange::new(ip("10.0.0.0"), 8)],
+            vec![IpRange::single(ip("10.0.0.1"))],
+        );
+        assert!(filter.is_allowed(&ip("10.0.0.2")));
+        assert!(!filter.is_allowed(&ip("10.0.0.1")));
+        assert!(!filter.is_allowed(&ip("::ffff:10.0.0.1")));
+        assert!(!filter.is_allowed(&ip("192.168.0.1")));
+    }
+
+    #[test]
+    fn empty_filter_allows_everything() {
+        let filter = IpFilter::default();
+        assert!(filter.is_allowed(&ip("10.0.0.1")));
+        assert!(filter.is_allowed(&ip("::1")));
+    }
+
+    #[test]
+    fn deny_applies_to_mapped_addresses() {
+        let filter = IpFilter::new(Vec::new(), vec![IpRange::new(ip("203.0.113.0"), 24)]);
+        assert!(!filter.is_allowed(&ip("203.0.113.7")));
+        assert!(!filter.is_allowed(&ip("::ffff:203.0.113.7")));
+        assert!(filter.is_allowed(&ip("::ffff:198.51.100.7")));
+    }
+}
@@ -22,16 +22,27 @@ pub use naia_socket_shared::LinkConditionerConfig;
 
 mod error;
 mod impls;
+mod ip_filter;
 mod link_conditioner;
+mod loopback;
 mod message_sender;
 mod packet;
+mod server_socket_config;
+mod server_socket_metrics;
 mod server_socket_trait;
 
 pub use error::NaiaServerSocketError;
 pub use impls::ServerSocket;
+pub use ip_filter::{IpFilter, IpRange};
+pub use loopback::{LoopbackClients, LoopbackServerSocket};
 pub use message_sender::MessageSender;
 pub use naia_socket_shared::find_my_ip_address;
 pub use packet::Packet;
+pub use server_socket_config::{
+    AllowedOrigins, ServerSocketConfig, SessionRateLimit, DEFAULT_SESSION_PATH,
+    MAX_UDP_PAYLOAD_SIZE,
+};
+pub use server_socket_metrics::ServerSocketMetrics;
 pub use server_socket_trait::ServerSocketTrait;
 
 cfg_if! {
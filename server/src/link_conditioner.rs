@@ -1,13 +1,13 @@
 use async_io::Timer;
 use async_trait::async_trait;
 use futures_util::{pin_mut, select, FutureExt};
-use std::time::Duration;
+use std::{net::SocketAddr, time::Duration};
 
 use naia_socket_shared::{link_condition_logic, LinkConditionerConfig, TimeQueue};
 
 use super::{
     error::NaiaServerSocketError, message_sender::MessageSender, packet::Packet,
-    server_socket_trait::ServerSocketTrait,
+    server_socket_metrics::ServerSocketMetrics, server_socket_trait::ServerSocketTrait,
 };
 
 pub struct LinkConditioner {
@@ -91,6 +91,34 @@ impl ServerSocketTrait for LinkConditioner {
         self.inner_socket.get_sender()
     }
 
+    fn session_addr(&self) -> SocketAddr {
+        self.inner_socket.session_addr()
+    }
+
+    fn webrtc_addr(&self) -> SocketAddr {
+        self.inner_socket.webrtc_addr()
+    }
+
+    fn connected_clients(&self) -> Vec<SocketAddr> {
+        self.inner_socket.connected_clients()
+    }
+
+    fn metrics(&self) -> ServerSocketMetrics {
+        self.inner_socket.metrics()
+    }
+
+    async fn broadcast(&mut self, payload: &[u8]) {
+        self.inner_socket.broadcast(payload).await
+    }
+
+    async fn disconnect(&mut self, address: &SocketAddr) -> bool {
+        self.inner_socket.disconnect(address).await
+    }
+
+    async fn shutdown(self: Box<Self>) {
+        self.inner_socket.shutdown().await
+    }
+
     fn with_link_conditioner(
         self: Box<Self>,
         config: &LinkConditionerConfig,
@@ -0,0 +1,370 @@
+use async_trait::async_trait;
+use futures_channel::mpsc;
+use futures_util::{pin_mut, select, FutureExt, StreamExt};
+use std::{collections::HashSet, error::Error, net::SocketAddr};
+
+use naia_socket_shared::LinkConditionerConfig;
+
+use crate::{
+    error::NaiaServerSocketError, link_conditioner::LinkConditioner, message_sender::MessageSender,
+    server_socket_metrics::ServerSocketMetrics, Packet, ServerSocketConfig, ServerSocketTrait,
+};
+
+/// A socket server which never touches the network. Packets from clients are
+/// injected, and packets sent to clients are captured, in-process through the
+/// accompanying LoopbackClients handle. Useful for testing code built on top
+/// of a Server Socket without standing up a real one
+#[derive(Debug)]
+pub struct LoopbackServerSocket {
+    address: SocketAddr,
+    from_client_receiver: mpsc::UnboundedReceiver<Packet>,
+    to_client_sender: mpsc::Sender<Packet>,
+    to_client_receiver: mpsc::Receiver<Packet>,
+    captured_sender: mpsc::UnboundedSender<Packet>,
+    // Kept in the order clients were first seen, so that iterating over them
+    // is deterministic
+    connected_clients: Vec<SocketAddr>,
+    // Clients disconnected while Packets for them may still be queued
+    disconnected: HashSet<SocketAddr>,
+    metrics: ServerSocketMetrics,
+}
+
+impl LoopbackServerSocket {
+    /// Returns a new LoopbackServerSocket reporting the given address as its
+    /// own, along with the handle used to talk to it
+    pub fn pair(address: SocketAddr) -> (Box<dyn ServerSocketTrait>, LoopbackClients) {
+        let (from_client_sender, from_client_receiver) = mpsc::unbounded();
+        let (to_client_sender, to_client_receiver) =
+            mpsc::channel(ServerSocketConfig::default().send_queue_size);
+        let (captured_sender, captured_receiver) = mpsc::unbounded();
+
+        let socket = LoopbackServerSocket {
+            address,
+            from_client_receiver,
+            to_client_sender,
+            to_client_receiver,
+            captured_sender,
+            connected_clients: Vec::new(),
+            disconnected: HashSet::new(),
+            metrics: ServerSocketMetrics::default(),
+        };
+
+        let clients = LoopbackClients {
+            from_client_sender,
+            captured_receiver,
+        };
+
+        (Box::new(socket), clients)
+    }
+
+    fn accept(&mut self, packet: Packet) -> Packet {
+        if !self.connected_clients.contains(&packet.address()) {
+            self.connected_clients.push(packet.address());
+        }
+        self.metrics.record_received(packet.payload().len());
+        packet
+    }
+
+    fn capture(&mut self, packet: Packet) {
+        self.metrics.record_sent(packet.payload().len());
+        // If the LoopbackClients handle is gone, nobody is listening anyway
+        let _ = self.captured_sender.unbounded_send(packet);
+    }
+}
+
+#[async_trait]
+impl ServerSocketTrait for LoopbackServerSocket {
+    async fn receive(&mut self) -> Result<Packet, NaiaServerSocketError> {
+        enum Next {
+            FromClientMessage(Option<Packet>),
+            ToClientMessage(Packet),
+        }
+
+        loop {
+            if !self.disconnected.is_empty() {
+                match self.to_client_receiver.try_recv() {
+                    Ok(packet) => {
+                        if !self.disconnected.contains(&packet.address()) {
+                            self.capture(packet);
+                        }
+                        continue;
+                    }
+                    Err(_) => self.disconnected.clear(),
+                }
+            }
+
+            let next = {
+                let to_client_receiver_next = self.to_client_receiver.next().fuse();
+                pin_mut!(to_client_receiver_next);
+
+                let from_client_receiver_next = self.from_client_receiver.next().fuse();
+                pin_mut!(from_client_receiver_next);
+
+                select! {
+                    from_client_message = from_client_receiver_next => {
+                        Next::FromClientMessage(from_client_message)
+                    }
+                    to_client_message = to_client_receiver_next => {
+                        Next::ToClientMessage(
+                            to_client_message.expect("to server message receiver closed")
+                        )
+                    }
+                }
+            };
+
+            match next {
+                Next::FromClientMessage(Some(packet)) => {
+                    return Ok(self.accept(packet));
+                }
+                Next::FromClientMessage(None) => {
+                    let error: Box<dyn Error + Send + Sync> =
+                        "loopback clients were dropped".into();
+                    return Err(NaiaServerSocketError::Wrapped(error));
+                }
+                Next::ToClientMessage(packet) => {
+                    self.capture(packet);
+                }
+            }
+        }
+    }
+
+    async fn receive_batch(&mut self, max: usize) -> Vec<Result<Packet, NaiaServerSocketError>> {
+        let mut results = Vec::new();
+        if max == 0 {
+            return results;
+        }
+
+        results.push(self.receive().await);
+
+        while results.len() < max {
+            match self.from_client_receiver.try_recv() {
+                Ok(packet) => results.push(Ok(self.accept(packet))),
+                Err(_) => break,
+            }
+        }
+
+        results
+    }
+
+    fn get_sender(&mut self) -> MessageSender {
+        return MessageSender::new(self.to_client_sender.clone());
+    }
+
+    fn session_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn webrtc_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn connected_clients(&self) -> Vec<SocketAddr> {
+        self.connected_clients.clone()
+    }
+
+    fn metrics(&self) -> ServerSocketMetrics {
+        let mut metrics = self.metrics;
+        metrics.connected_clients = self.connected_clients.len();
+        metrics
+    }
+
+    async fn broadcast(&mut self, payload: &[u8]) {
+        for address in self.connected_clients() {
+            self.capture(Packet::new(address, payload.to_vec()));
+        }
+    }
+
+    async fn disconnect(&mut self, address: &SocketAddr) -> bool {
+        match self
+            .connected_clients
+            .iter()
+            .position(|client| client == address)
+        {
+            Some(index) => {
+                self.connected_clients.remove(index);
+            }
+            None => return false,
+        }
+
+        // Anything still queued for the client is dropped by `receive`
+        self.disconnected.insert(*address);
+
+        true
+    }
+
+    async fn shutdown(mut self: Box<Self>) {
+        self.to_client_receiver.close();
+        while let Ok(packet) = self.to_client_receiver.try_recv() {
+            self.capture(packet);
+        }
+    }
+
+    fn with_link_conditioner(
+        self: Box<Self>,
+        config: &LinkConditionerConfig,
+    ) -> Box<dyn ServerSocketTrait> {
+        Box::new(LinkConditioner::new(config, self))
+    }
+}
+
+/// The client side of a LoopbackServerSocket, used to inject packets into the
+/// socket and to inspect the packets it has sent
+#[derive(Debug)]
+pub struct LoopbackClients {
+    from_client_sender: mpsc::UnboundedSender<Packet>,
+    captured_receiver: mpsc::UnboundedReceiver<Packet>,
+}
+
+impl LoopbackClients {
+    /// Delivers a Packet to the socket, as though it had been sent by the
+    /// client at the Packet's address. A client counts as connected from the
+    /// moment its first Packet is received
+    pub fn send(&mut self, packet: Packet) {
+        // If the socket is gone, there is nobody to deliver to
+        let _ = self.from_client_sender.unbounded_send(packet);
+    }
+
+    /// Returns the next Packet the socket has sent to a client, if any,
+    /// without waiting. Packets only show up here once the socket has
+    /// processed them, which happens while it is being polled in `receive`
+    pub fn try_receive(&mut self) -> Option<Packet> {
+        self.captured_receiver.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use futures_util::FutureExt;
+
+    use super::LoopbackServerSocket;
+    use crate::Packet;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn sent_packets_are_received_and_replies_captured() {
+        let (mut socket, mut clients) = LoopbackServerSocket::pair(address(14191));
+        let mut sender = socket.get_sender();
+
+        clients.send(Packet::new(address(14192), b"ping".to_vec()));
+
+        let packet = async_io::block_on(socket.receive()).unwrap();
+        assert_eq!(packet, Packet::new(address(14192), b"ping".to_vec()));
+
+        async_io::block_on(sender.send(Packet::new(packet.address(), b"pong".to_vec()))).unwrap();
+        assert!(socket.receive().now_or_never().is_none());
+
+        assert_eq!(
+            clients.try_receive(),
+            Some(Packet::new(address(14192), b"pong".to_vec()))
+        );
+        assert_eq!(clients.try_receive(), None);
+    }
+
+    #[test]
+    fn broadcast_follows_the_order_clients_were_first_seen() {
+        let (mut socket, mut clients) = LoopbackServerSocket::pair(address(14191));
+
+        for port in &[14194, 14192, 14193, 14192] {
+            clients.send(Packet::new(address(*port), b"hello".to_vec()));
+            async_io::block_on(socket.receive()).unwrap();
+        }
+
+        let expected = vec![address(14194), address(14192), address(14193)];
+        assert_eq!(socket.connected_clients(), expected);
+
+        async_io::block_on(socket.broadcast(b"all"));
+        for client_address in expected {
+            assert_eq!(
+                clients.try_receive(),
+                Some(Packet::new(client_address, b"all".to_vec()))
+            );
+        }
+        assert_eq!(clients.try_receive(), None);
+    }
+
+    #[test]
+    fn receive_batch_collects_what_is_available() {
+        let (mut socket, mut clients) = LoopbackServerSocket::pair(address(14191));
+
+        for index in 0..3 {
+            clients.send(Packet::new(address(14192), vec![index]));
+        }
+
+        let batch = async_io::block_on(socket.receive_batch(2));
+        let payloads: Vec<u8> = batch
+            .iter()
+            .map(|r| r.as_ref().unwrap().payload()[0])
+            .collect();
+        assert_eq!(payloads, vec![0, 1]);
+
+        let batch = async_io::block_on(socket.receive_batch(2));
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].as_ref().unwrap().payload(), &[2]);
+    }
+
+    #[test]
+    fn disconnect_drops_only_that_clients_queued_packets() {
+        let (mut socket, mut clients) = LoopbackServerSocket::pair(address(14191));
+        let mut sender = socket.get_sender();
+
+        for port in &[14192, 14193] {
+            clients.send(Packet::new(address(*port), b"hello".to_vec()));
+            async_io::block_on(socket.receive()).unwrap();
+        }
+
+        for port in &[14192, 14193, 14192] {
+            sender
+                .try_send(Packet::new(address(*port), b"bye".to_vec()))
+                .unwrap();
+        }
+
+        assert!(async_io::block_on(socket.disconnect(&address(14192))));
+        assert!(!async_io::block_on(socket.disconnect(&address(14192))));
+        assert_eq!(clients.try_receive(), None);
+
+        assert!(socket.receive().now_or_never().is_none());
+        assert_eq!(
+            clients.try_receive(),
+            Some(Packet::new(address(14193), b"bye".to_vec()))
+        );
+        assert_eq!(clients.try_receive(), None);
+        assert_eq!(socket.connected_clients(), vec![address(14193)]);
+    }
+
+    #[test]
+    fn dropping_receive_loses_no_packets() {
+        let (mut socket, mut clients) = LoopbackServerSocket::pair(address(14191));
+        let mut sender = socket.get_sender();
+
+        // Nothing is available yet, so this future is dropped while pending
+        assert!(socket.receive().now_or_never().is_none());
+
+        sender
+            .try_send(Packet::new(address(14192), b"to client".to_vec()))
+            .unwrap();
+        clients.send(Packet::new(address(14192), b"from client".to_vec()));
+
+        // Each future is polled once and dropped, whether or not it is done
+        let mut received = None;
+        for _ in 0..2 {
+            if let Some(result) = socket.receive().now_or_never() {
+                received = Some(result.unwrap());
+            }
+        }
+
+        assert_eq!(
+            received,
+            Some(Packet::new(address(14192), b"from client".to_vec()))
+        );
+        assert_eq!(
+            clients.try_receive(),
+            Some(Packet::new(address(14192), b"to client".to_vec()))
+        );
+        assert_eq!(clients.try_receive(), None);
+    }
+}
@@ -1,6 +1,6 @@
 use std::error::Error;
 
-use crate::Packet;
+use crate::{error::NaiaServerSocketError, Packet};
 
 use futures_channel;
 use futures_util::SinkExt;
@@ -28,4 +28,21 @@ impl MessageSender {
             }
         }
     }
+
+    /// Queue a Packet to be sent to a client without waiting. If the queue is
+    /// full, returns `NaiaServerSocketError::QueueFull` containing the Packet
+    pub fn try_send(&mut self, packet: Packet) -> Result<(), NaiaServerSocketError> {
+        match self.internal.try_send(packet) {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                if error.is_full() {
+                    Err(NaiaServerSocketError::QueueFull(error.into_inner()))
+                } else {
+                    Err(NaiaServerSocketError::Wrapped(Box::new(
+                        error.into_send_error(),
+                    )))
+                }
+            }
+        }
+    }
 }
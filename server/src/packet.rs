@@ -1,4 +1,4 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, str::Utf8Error};
 
 /// A Packet that can be sent to a Client
 #[derive(Debug, Eq, PartialEq)]
@@ -28,6 +28,12 @@ impl Packet {
         &self.payload
     }
 
+    /// Get at the underlying payload of the packet as text, without copying
+    /// it. Returns an error if the payload is not valid UTF-8
+    pub fn payload_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.payload)
+    }
+
     /// Get the address the Packet is assigned to
     pub fn address(&self) -> SocketAddr {
         self.address
@@ -0,0 +1,134 @@
+use crate::{IpFilter, IpRange};
+
+/// The default HTTP path clients request a new WebRTC session on
+pub const DEFAULT_SESSION_PATH: &str = "/new_rtc_session";
+
+/// The largest payload that can be carried by a single UDP datagram over IPv4
+pub const MAX_UDP_PAYLOAD_SIZE: usize = 65507;
+
+/// Contains configuration used to initialize a ServerSocket
+#[derive(Debug, Clone)]
+pub struct ServerSocketConfig {
+    /// Size in bytes of the largest datagram which can be read. Datagrams
+    /// larger than this, or than `max_payload_size`, are rejected as too
+    /// large rather than passed on truncated. There is no benefit to setting
+    /// it above `MAX_UDP_PAYLOAD_SIZE`, but servers which only ever expect
+    /// small packets may lower it. Only used by the UDP implementation, the
+    /// WebRTC implementation manages its own receive buffer
+    pub receive_buffer_size: usize,
+    /// Decides which source addresses are accepted, both when requesting a
+    /// WebRTC session and for every incoming datagram. Accepts everyone by
+    /// default
+    pub ip_filter: IpFilter,
+    /// Whether to log the full SDP answer sent in response to each WebRTC
+    /// session request. Useful when diagnosing failed connections, as it
+    /// shows exactly which candidate & fingerprint were handed to the client
+    pub log_session_answers: bool,
+    /// The HTTP path which accepts `POST` requests for a new WebRTC session.
+    /// Change this when serving signaling from behind a reverse proxy which
+    /// routes a different path to the socket. Any query string on the request
+    /// is ignored. The clients in this repository always request
+    /// `DEFAULT_SESSION_PATH`, so with any other path they can only reach the
+    /// server through a proxy which rewrites their requests to it
+    pub session_path: String,
+    /// Which origins the session server allows to make cross-origin requests,
+    /// via the `Access-Control-Allow-Origin` header on its responses
+    pub allowed_origins: AllowedOrigins,
+    /// How many outgoing Packets may wait to be sent before a MessageSender
+    /// stops accepting more. The queue is shared by every client of the
+    /// socket, and each waiting Packet holds its own payload allocation, so
+    /// memory use grows with this value multiplied by the typical packet
+    /// size. Servers sending to thousands of clients each tick will want it
+    /// at least as large as the number of packets sent per tick
+    pub send_queue_size: usize,
+    /// The largest payload accepted from a client, in bytes. Larger packets
+    /// are rejected with `NaiaServerSocketError::PayloadTooLarge`, and over
+    /// WebRTC the offending client is also disconnected
+    pub max_payload_size: usize,
+    /// Limits how often a single IP address, or IPv6 /64 network, may request
+    /// a new WebRTC session. Requests over the limit are answered with
+    /// `429 Too Many Requests`. Unlimited by default
+    pub session_rate_limit: Option<SessionRateLimit>,
+    /// The maximum number of clients, whether connected, still completing
+    /// their handshake, or granted a session they have yet to use, the server
+    /// will accept. Once reached, session requests are answered with
+    /// `503 Service Unavailable` and a "server full" body. Clients which
+    /// leave are only noticed each time the socket receives, so the count may
+    /// briefly lag behind. Unlimited by default
+    pub max_clients: Option<usize>,
+}
+
+impl Default for ServerSocketConfig {
+    fn default() -> Self {
+        ServerSocketConfig {
+            receive_buffer_size: MAX_UDP_PAYLOAD_SIZE,
+            ip_filter: IpFilter::default(),
+            log_session_answers: false,
+            session_path: DEFAULT_SESSION_PATH.to_string(),
+            allowed_origins: AllowedOrigins::Any,
+            send_queue_size: 256,
+            max_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            session_rate_limit: None,
+            max_clients: None,
+        }
+    }
+}
+
+/// Describes which origins may make cross-origin session requests
+#[derive(Debug, Clone)]
+pub enum AllowedOrigins {
+    /// Any origin is allowed, responses carry `Access-Control-Allow-Origin: *`
+    Any,
+    /// Only the listed origins are allowed. The header is set to the
+    /// request's `Origin` when it matches one of these, and omitted otherwise
+    List(Vec<String>),
+    /// The `Access-Control-Allow-Origin` header is never sent
+    Omit,
+}
+
+impl AllowedOrigins {
+    /// Returns the value of the `Access-Control-Allow-Origin` header to
+    /// respond with, given the `Origin` header of the request, if any
+    pub fn allow_origin(&self, request_origin: Option<&str>) -> Option<String> {
+        match self {
+            AllowedOrigins::Any => Some("*".to_string()),
+            AllowedOrigins::List(origins) => request_origin
+                .filter(|origin| origins.iter().any(|allowed| allowed == origin))
+                .map(|origin| origin.to_string()),
+            AllowedOrigins::Omit => None,
+        }
+    }
+
+    /// Returns whether the header returned by `allow_origin` depends on the
+    /// request's `Origin`, in which case responses must carry `Vary: Origin`
+    /// so that caches don't hand one origin's response to another
+    pub fn varies_by_origin(&self) -> bool {
+        match self {
+            AllowedOrigins::List(_) => true,
+            AllowedOrigins::Any | AllowedOrigins::Omit => false,
+        }
+    }
+}
+
+/// Describes a token bucket limiting the rate of session requests from each
+/// IP address
+#[derive(Debug, Clone)]
+pub struct SessionRateLimit {
+    /// How many requests an address may make in quick succession
+    pub burst: u32,
+    /// How many requests per second an address regains, up to `burst`
+    pub requests_per_second: f32,
+    /// Ranges of addresses which are never rate limited
+    pub exempt: Vec<IpRange>,
+}
+
+impl SessionRateLimit {
+    /// Creates a new SessionRateLimit, with no exempt addresses
+    pub fn new(burst: u32, requests_per_second: f32) -> Self {
+        SessionRateLimit {
+            burst,
+            requests_per_second,
+            exempt: Vec::new(),
+        }
+    }
+}
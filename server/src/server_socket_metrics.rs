@@ -0,0 +1,111 @@
+use std::fmt::Write;
+
+/// A snapshot of counters describing the activity of a Server Socket since it
+/// started listening
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ServerSocketMetrics {
+    /// The number of clients connected at the time of the snapshot
+    pub connected_clients: usize,
+    /// The number of clients still completing their handshake, or shutting
+    /// down, at the time of the snapshot
+    pub handshaking_clients: usize,
+    /// The number of packets received from clients and handed to the caller
+    pub packets_received: u64,
+    /// The number of payload bytes received from clients
+    pub bytes_received: u64,
+    /// The number of packets from clients which were dropped, for coming
+    /// from a filtered address or exceeding the maximum payload size. These
+    /// don't count towards `packets_received`
+    pub packets_dropped: u64,
+    /// The number of packets successfully sent to clients
+    pub packets_sent: u64,
+    /// The number of payload bytes successfully sent to clients
+    pub bytes_sent: u64,
+    /// The number of times receiving from the underlying socket failed
+    pub receive_errors: u64,
+    /// The number of packets which could not be sent to a client
+    pub send_errors: u64,
+}
+
+impl ServerSocketMetrics {
+    pub(crate) fn record_received(&mut self, payload_len: usize) {
+        self.packets_received += 1;
+        self.bytes_received += payload_len as u64;
+    }
+
+    pub(crate) fn record_sent(&mut self, payload_len: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += payload_len as u64;
+    }
+
+    /// Renders the metrics in the OpenMetrics text exposition format, with
+    /// every metric name starting with the given prefix. The terminating
+    /// `# EOF` line is left off, so the output can be combined with that of
+    /// other sockets or metrics
+    pub fn to_open_metrics(&self, prefix: &str) -> String {
+        let mut out = String::new();
+
+        let mut gauge = |name: &str, value: u64| {
+            let _ = writeln!(out, "# TYPE {}_{} gauge", prefix, name);
+            let _ = writeln!(out, "{}_{} {}", prefix, name, value);
+        };
+        gauge("connected_clients", self.connected_clients as u64);
+        gauge("handshaking_clients", self.handshaking_clients as u64);
+
+        let mut counter = |name: &str, value: u64| {
+            let _ = writeln!(out, "# TYPE {}_{} counter", prefix, name);
+            let _ = writeln!(out, "{}_{}_total {}", prefix, name, value);
+        };
+        counter("packets_received", self.packets_received);
+        counter("bytes_received", self.bytes_received);
+        counter("packets_dropped", self.packets_dropped);
+        counter("packets_sent", self.packets_sent);
+        counter("bytes_sent", self.bytes_sent);
+        counter("receive_errors", self.receive_errors);
+        counter("send_errors", self.send_errors);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerSocketMetrics;
+
+    #[test]
+    fn open_metrics_suffixes_only_counters() {
+        let metrics = ServerSocketMetrics {
+            connected_clients: 3,
+            handshaking_clients: 1,
+            packets_received: 10,
+            bytes_received: 200,
+            packets_dropped: 2,
+            packets_sent: 8,
+            bytes_sent: 160,
+            receive_errors: 0,
+            send_errors: 1,
+        };
+
+        let expected = "\
+# TYPE naia_connected_clients gauge
+naia_connected_clients 3
+# TYPE naia_handshaking_clients gauge
+naia_handshaking_clients 1
+# TYPE naia_packets_received counter
+naia_packets_received_total 10
+# TYPE naia_bytes_received counter
+naia_bytes_received_total 200
+# TYPE naia_packets_dropped counter
+naia_packets_dropped_total 2
+# TYPE naia_packets_sent counter
+naia_packets_sent_total 8
+# TYPE naia_bytes_sent counter
+naia_bytes_sent_total 160
+# TYPE naia_receive_errors counter
+naia_receive_errors_total 0
+# TYPE naia_send_errors counter
+naia_send_errors_total 1
+";
+        assert_eq!(metrics.to_open_metrics("naia"), expected);
+    }
+}
@@ -1,21 +1,97 @@
 use async_trait::async_trait;
+use futures_util::{
+    stream::{self, Stream},
+    FutureExt,
+};
+use std::net::SocketAddr;
 
 use naia_socket_shared::LinkConditionerConfig;
 
-use super::{message_sender::MessageSender, packet::Packet};
+use super::{
+    message_sender::MessageSender, packet::Packet, server_socket_metrics::ServerSocketMetrics,
+};
 use crate::error::NaiaServerSocketError;
 
 /// Defines the functionality of a Naia Server Socket
 #[async_trait]
 pub trait ServerSocketTrait: Send + Sync {
-    /// Receive a new packet from the socket, or a tick event
+    /// Receive a new packet from the socket, or a tick event.
+    /// Cancellation safe: if the returned future is dropped before completing,
+    /// no received packet is lost, and a packet already taken from the send
+    /// queue is kept and sent on the next call. Over WebRTC this only holds
+    /// up to the RTC server: a datagram it has already taken off its own
+    /// queue is lost if the future is dropped mid-send, and the Packet may
+    /// then be sent twice
     async fn receive(&mut self) -> Result<Packet, NaiaServerSocketError>;
+    /// Receive a packet if one is immediately available, without waiting.
+    /// This lets the socket be driven step by step from an application's own
+    /// event loop, with no async runtime involved. Each call which finds
+    /// nothing drops a `receive` in progress, so over WebRTC this carries the
+    /// risk described above on every such call, and `receive` should be
+    /// preferred there
+    fn try_receive(&mut self) -> Option<Result<Packet, NaiaServerSocketError>> {
+        self.receive().now_or_never()
+    }
+    /// Receive up to `max` results at once. Waits for the first, then keeps
+    /// collecting for as long as more can be read without waiting, which
+    /// saves a wakeup per packet when draining a burst. Sockets which can't
+    /// check for more without dropping a `receive` in progress, such as
+    /// WebRTC, return just the first
+    async fn receive_batch(&mut self, max: usize) -> Vec<Result<Packet, NaiaServerSocketError>> {
+        let mut results = Vec::new();
+        if max == 0 {
+            return results;
+        }
+
+        results.push(self.receive().await);
+
+        results
+    }
     /// Gets a MessageSender you can use to send messages through the Server
     /// Socket
     fn get_sender(&mut self) -> MessageSender;
+    /// Gets the address the socket is accepting session requests on. For UDP
+    /// this is the address of the single underlying socket
+    fn session_addr(&self) -> SocketAddr;
+    /// Gets the address the socket is exchanging data with clients on. For
+    /// UDP this is the address of the single underlying socket. WebRTC can't
+    /// report the port it is bound to, so listening there with a port of 0 is
+    /// rejected rather than this returning a `:0` address
+    fn webrtc_addr(&self) -> SocketAddr;
+    /// Gets the addresses of all currently connected clients. UDP has no
+    /// notion of a connection, so this is always empty there
+    fn connected_clients(&self) -> Vec<SocketAddr>;
+    /// Gets a snapshot of the counters describing the socket's activity so far
+    fn metrics(&self) -> ServerSocketMetrics;
+    /// Sends the given payload to every connected client. Clients which are
+    /// not yet ready to receive messages are skipped. UDP has no notion of a
+    /// connection, so this sends nothing there
+    async fn broadcast(&mut self, payload: &[u8]);
+    /// Disconnects the client at the given address, dropping any messages
+    /// still queued for it. Returns whether the client was connected. UDP has
+    /// no notion of a connection, so this always returns false there
+    async fn disconnect(&mut self, address: &SocketAddr) -> bool;
+    /// Shuts the socket down, stopping new sessions from being accepted,
+    /// delivering any messages which are already queued, and disconnecting
+    /// all clients. Resolves once the underlying listeners have been closed
+    async fn shutdown(self: Box<Self>);
     /// Wraps the current socket in a LinkConditioner
     fn with_link_conditioner(
         self: Box<Self>,
         config: &LinkConditionerConfig,
     ) -> Box<dyn ServerSocketTrait>;
 }
+
+impl dyn ServerSocketTrait {
+    /// Converts the socket into a Stream which yields the result of each call
+    /// to `receive`. Any MessageSender should be retrieved with `get_sender`
+    /// beforehand, as the socket is consumed
+    pub fn into_stream(
+        self: Box<Self>,
+    ) -> impl Stream<Item = Result<Packet, NaiaServerSocketError>> + Send {
+        stream::unfold(self, |mut socket| async move {
+            let result = socket.receive().await;
+            Some((result, socket))
+        })
+    }
+}